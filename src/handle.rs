@@ -1,45 +1,133 @@
-use std::cmp::min;
+use std::any::Any;
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::fs::File;
 use std::io;
 use std::io::Write;
 use std::os::raw::{c_int, c_uint, c_void};
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::pin::Pin;
-use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
-use std::time::Duration;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
 
-use bytes::{Buf, Bytes};
+use bytes::Bytes;
 use chunked_bytes::ChunkedBytes;
 use evdi_sys::*;
-use filedescriptor::{poll, pollfd, POLLIN};
+use futures::Stream;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{Mutex, Notify};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::BroadcastStream;
 
 use crate::device_config::DeviceConfig;
+use crate::{DrmFormat, UnrecognizedFourcc};
+
+/// An event emitted by the kernel module and forwarded by the driver task.
+///
+/// Yielded by the [`HandleEvents`] stream so a compositor can react to display
+/// state changes without owning a dedicated dispatch thread.
+#[derive(Debug, Clone)]
+pub enum HandleEvent {
+    /// The display mode changed.
+    Mode(evdi_mode),
+    /// The hardware cursor moved.
+    CursorMove(CursorMove),
+    /// The hardware cursor bitmap changed.
+    CursorChange(CursorChange),
+    /// DDC/CI data was received from the host.
+    DdcCiData(DdcCiData),
+}
+
+/// A hardware cursor move, reported out-of-band from the framebuffer.
+#[derive(Debug, Clone)]
+pub struct CursorMove {
+    pub x: i32,
+    pub y: i32,
+    pub visible: bool,
+}
+
+/// A hardware cursor bitmap change, reported out-of-band from the framebuffer.
+#[derive(Debug, Clone)]
+pub struct CursorChange {
+    pub pixels: Bytes,
+    pub width: u32,
+    pub height: u32,
+    pub hot_x: i32,
+    pub hot_y: i32,
+    pub pixel_format: u32,
+}
+
+/// DDC/CI data received from the host.
+#[derive(Debug, Clone)]
+pub struct DdcCiData {
+    pub bytes: Bytes,
+}
+
+/// The set of channels the driver task pushes kernel events into.
+///
+/// A `Box<Dispatch>` is kept alive for the lifetime of the driver task and its
+/// address is handed to `evdi_handle_events` as `user_data`, so the `extern "C"`
+/// handlers can recover it without touching the [`Handle`] itself.
+struct Dispatch {
+    mode: UnboundedSender<evdi_mode>,
+    update_ready: UnboundedSender<BufferID>,
+    cursor_set: UnboundedSender<CursorChange>,
+    cursor_move: UnboundedSender<CursorMove>,
+    events: broadcast::Sender<HandleEvent>,
+    // `evdi_cursor_move` carries no visibility flag, so we remember the last `enabled` state from
+    // the most recent cursor-set event and report it on subsequent moves.
+    cursor_visible: AtomicBool,
+}
+
+/// An `evdi_handle` is just a pointer into the C library; it is safe to move the
+/// dispatch half into the driver task as long as `evdi_handle_events` is only
+/// ever called from that one task.
+struct DriverHandle(evdi_handle);
+unsafe impl Send for DriverHandle {}
 
 /// Represents an EVDI handle that is connected and ready.
 ///
+/// Events are dispatched by a background tokio task driven by the fd returned
+/// from `evdi_get_event_ready`, so integrating with an existing reactor does not
+/// require a thread spinning `request_events` in a loop.
+///
 /// Automatically disconnected on drop.
 #[derive(Debug)]
 pub struct Handle {
     handle: evdi_handle,
     device_config: DeviceConfig,
     buffers: HashMap<BufferID, Buffer>,
-    mode: Receiver<evdi_mode>,
-    mode_sender: Sender<evdi_mode>,
+    mode: Mutex<UnboundedReceiver<evdi_mode>>,
+    update_ready: UnboundedReceiver<BufferID>,
+    cursor_set: Mutex<UnboundedReceiver<CursorChange>>,
+    cursor_move: Mutex<UnboundedReceiver<CursorMove>>,
+    events: broadcast::Sender<HandleEvent>,
+    /// Signals the driver task to stop. Only [`Self::close`] waits on this completing; `Drop`
+    /// can't await async work, so it falls back to aborting the task instead.
+    stop: Arc<Notify>,
+    driver: JoinHandle<()>,
+    /// Set once `evdi_disconnect`/`evdi_close` have run, so [`Self::close`] and `Drop` don't both
+    /// tear the handle down.
+    closed: bool,
 }
 
 impl Handle {
     /// Register a buffer with the handle.
     ///
-    /// ```
+    /// ```no_run
     /// # use evdi::{device::Device, device_config::DeviceConfig, handle::{Buffer, BufferID}};
-    /// # use std::time::Duration;
-    /// # let timeout = Duration::from_secs(1);
-    /// # let mut handle = Device::get().unwrap().open().connect(&DeviceConfig::sample(), timeout);
-    /// # handle.request_events();
-    /// let mode = handle.receive_mode(timeout).unwrap();
+    /// # async fn f() {
+    /// # let mut handle = Device::get().unwrap().open().connect(&DeviceConfig::sample());
+    /// let mode = handle.receive_mode().await.unwrap();
     /// let buf = Buffer::new(BufferID::new(1), &mode);
     ///
     /// handle.register_buffer(buf);
+    /// # }
     /// ```
     pub fn register_buffer(&mut self, buffer: Buffer) {
         let id = buffer.id.clone();
@@ -65,34 +153,39 @@ impl Handle {
 
     /// Ask the kernel module to update a buffer with the current display pixels.
     ///
-    /// Blocks until the update is complete.
+    /// Awaits the update-ready notification from the driver task if the data is
+    /// not already available.
     ///
-    /// ```
+    /// ```no_run
     /// # use evdi::{device::Device, device_config::DeviceConfig, handle::{Buffer, BufferID}};
-    /// # use std::time::Duration;
-    /// # let timeout = Duration::from_secs(1);
-    /// # let mut handle = Device::get().unwrap().open().connect(&DeviceConfig::sample(), timeout);
-    /// # handle.request_events();
-    /// # let mode = handle.receive_mode(timeout).unwrap();
+    /// # async fn f() {
+    /// # let mut handle = Device::get().unwrap().open().connect(&DeviceConfig::sample());
+    /// # let mode = handle.receive_mode().await.unwrap();
     /// # let buf_id = BufferID::new(1);
     /// # let buf = Buffer::new(buf_id, &mode);
     /// # handle.register_buffer(buf);
-    /// let buf = handle.request_update(&buf_id, timeout).unwrap();
+    /// let buf = handle.request_update(&buf_id).await.unwrap();
     /// assert!(buf.dirty_rects().len() > 0);
+    /// # }
     /// ```
-    pub fn request_update(&mut self, id: &BufferID, timeout: Duration) -> Result<&Buffer, RecvTimeoutError> {
+    pub async fn request_update(&mut self, id: &BufferID) -> Option<&Buffer> {
         // NOTE: We need to take &mut self to ensure we can't be called concurrently. This is
         // required because evdi_grab_pixels grabs from the most recently updated buffer.
 
-        {
-            self.buf_required_mut(id).mark_updated();
-        }
+        self.buf_required_mut(id).mark_updated();
 
         let ready = unsafe { evdi_request_update(self.handle, id.0) };
         if !ready {
-            self.request_events();
-
-            self.buf_required(id).update_ready.recv_timeout(timeout)?;
+            // The driver task is the sole caller of evdi_handle_events, so we simply await the
+            // update-ready notification it forwards rather than dispatching events ourselves.
+            loop {
+                match self.update_ready.recv().await {
+                    Some(ready_id) if &ready_id == id => break,
+                    // A different buffer became ready; keep waiting for ours.
+                    Some(_) => continue,
+                    None => return None,
+                }
+            }
         }
 
         // We cast to *const and back to get around the borrow checker, which doesn't want us to be
@@ -108,115 +201,276 @@ impl Handle {
             )
         }
 
-        Ok(buf)
+        Some(buf)
     }
 
     pub fn enable_cursor_events(&self, enable: bool) {
         unsafe { evdi_enable_cursor_events(self.handle, enable); }
     }
 
-    /// Ask the kernel module to send us some events.
-    ///
-    /// I think this blocks, dispatches a certain number of events, and the then returns, so callers
-    /// should call in a loop. However, the docs aren't clear.
-    /// See <https://github.com/DisplayLink/evdi/issues/265>
-    pub fn request_events(&mut self) {
-        let mut ctx = evdi_event_context {
-            dpms_handler: None,
-            mode_changed_handler: Some(Self::mode_changed_handler_caller),
-            update_ready_handler: Some(Self::update_ready_handler_caller),
-            crtc_state_handler: None,
-            cursor_set_handler: None,
-            cursor_move_handler: None,
-            ddcci_data_handler: None,
-            // Safety: We cast to a mut pointer, but we never cast back to a mut reference
-            user_data: self as *mut _ as *mut c_void,
-        };
-        unsafe { evdi_handle_events(self.handle, &mut ctx) };
-    }
-
-    /// Blocks until a mode event is received.
+    /// Awaits the next mode event.
     ///
-    /// A mode event will not be received unless [`Self::request_events`] is called.
+    /// Mode events are forwarded by the driver task as soon as they arrive, so
+    /// no explicit dispatch call is required.
     ///
-    /// ```
+    /// ```no_run
     /// # use evdi::device::Device;
     /// # use evdi::device_config::DeviceConfig;
-    /// # use std::time::Duration;
+    /// # async fn f() {
     /// # let device: Device = Device::get().unwrap();
-    /// # let timeout = Duration::from_secs(1);
-    /// # let mut handle = device.open().connect(&DeviceConfig::sample(), timeout);
-    /// handle.request_events();
-    ///
-    /// let mode = handle.receive_mode(timeout).unwrap();
+    /// # let mut handle = device.open().connect(&DeviceConfig::sample());
+    /// let mode = handle.receive_mode().await.unwrap();
+    /// # }
     /// ```
-    pub fn receive_mode(&self, timeout: Duration) -> Result<evdi_mode, RecvTimeoutError> {
-        self.mode.recv_timeout(timeout)
+    pub async fn receive_mode(&self) -> Option<evdi_mode> {
+        self.mode.lock().await.recv().await
+    }
+
+    /// Awaits the next hardware-cursor bitmap change.
+    ///
+    /// Cursor events are only delivered after [`Self::enable_cursor_events`] has been called with
+    /// `true`. A compositor uses these to render the cursor as a separate overlay plane rather than
+    /// baked into the framebuffer.
+    pub async fn receive_cursor_change(&self) -> Option<CursorChange> {
+        self.cursor_set.lock().await.recv().await
+    }
+
+    /// Awaits the next hardware-cursor move.
+    ///
+    /// See [`Self::receive_cursor_change`] for the enabling requirement.
+    pub async fn receive_cursor_move(&self) -> Option<CursorMove> {
+        self.cursor_move.lock().await.recv().await
+    }
+
+    /// A stream of every [`HandleEvent`] the driver task forwards.
+    ///
+    /// Each call returns an independent subscription; events emitted before the
+    /// subscription is created are not replayed.
+    pub fn events(&self) -> HandleEvents {
+        HandleEvents {
+            inner: BroadcastStream::new(self.events.subscribe()),
+        }
+    }
+
+    /// Gracefully shut the handle down.
+    ///
+    /// Signals the driver task to stop and waits for it to actually exit before disconnecting and
+    /// closing the underlying `evdi_handle`, so the teardown can never run while the task is still
+    /// mid-call inside `evdi_handle_events`. Prefer this over letting `Handle` simply drop whenever
+    /// you're somewhere that can await: `Drop` can't wait on the driver task, so it falls back to
+    /// `JoinHandle::abort`, which accepts that same race as a pragmatic default.
+    pub async fn close(mut self) {
+        self.stop.notify_one();
+        let _ = (&mut self.driver).await;
+        self.teardown();
+    }
+
+    /// Disconnects and closes the underlying `evdi_handle`, if that hasn't already happened.
+    fn teardown(&mut self) {
+        if !self.closed {
+            self.closed = true;
+            unsafe {
+                evdi_disconnect(self.handle);
+                evdi_close(self.handle);
+            }
+        }
     }
 
     extern "C" fn mode_changed_handler_caller(mode: evdi_mode, user_data: *mut c_void) {
-        let handle = unsafe { Self::handle_from_user_data(user_data) };
-        if let Err(err) = handle.mode_sender.send(mode) {
+        let dispatch = unsafe { Self::dispatch_from_user_data(user_data) };
+        if let Err(err) = dispatch.mode.send(mode) {
             eprintln!("Dropping msg. Mode change receiver closed, but callback called: {:?}", err);
         }
+        let _ = dispatch.events.send(HandleEvent::Mode(mode));
     }
 
     extern "C" fn update_ready_handler_caller(buf: c_int, user_data: *mut c_void) {
-        let handle = unsafe { Self::handle_from_user_data(user_data) };
-
-        let id = BufferID(buf);
+        let dispatch = unsafe { Self::dispatch_from_user_data(user_data) };
+        if let Err(err) = dispatch.update_ready.send(BufferID(buf)) {
+            eprintln!("Dropping msg. Update ready receiver closed, but callback called: {:?}", err);
+        }
+    }
 
-        let send = handle.buffers
-            .get(&id)
-            .map(|buf| &buf.update_ready_sender);
+    extern "C" fn cursor_set_handler_caller(cursor: evdi_cursor_set, user_data: *mut c_void) {
+        let dispatch = unsafe { Self::dispatch_from_user_data(user_data) };
 
-        if let Some(send) = send {
-            if let Err(err) = send.send(()) {
-                eprintln!("Dropping msg. Update ready receiver closed, but callback called: {:?}", err);
-            }
+        let len = cursor.buffer_length as usize;
+        let pixels = if cursor.buffer.is_null() || len == 0 {
+            Bytes::new()
         } else {
-            eprintln!("Dropping msg. No update ready channel for buffer {:?}, but callback called", id);
-        }
+            // Safety: evdi hands us `buffer_length` valid bytes for the duration of this callback.
+            Bytes::copy_from_slice(unsafe {
+                std::slice::from_raw_parts(cursor.buffer as *const u8, len)
+            })
+        };
+
+        let visible = cursor.enabled != 0;
+        dispatch.cursor_visible.store(visible, Ordering::Relaxed);
+
+        let change = CursorChange {
+            pixels,
+            width: cursor.width,
+            height: cursor.height,
+            hot_x: cursor.hot_x,
+            hot_y: cursor.hot_y,
+            pixel_format: cursor.pixel_format,
+        };
+        let _ = dispatch.cursor_set.send(change.clone());
+        let _ = dispatch.events.send(HandleEvent::CursorChange(change));
     }
 
-    /// Safety: user_data must be a valid reference to a Handle.
-    unsafe fn handle_from_user_data<'a>(user_data: *mut c_void) -> &'a Handle {
-        (user_data as *mut Handle).as_ref().unwrap()
+    extern "C" fn cursor_move_handler_caller(cursor: evdi_cursor_move, user_data: *mut c_void) {
+        let dispatch = unsafe { Self::dispatch_from_user_data(user_data) };
+
+        let mv = CursorMove {
+            x: cursor.x,
+            y: cursor.y,
+            visible: dispatch.cursor_visible.load(Ordering::Relaxed),
+        };
+        let _ = dispatch.cursor_move.send(mv.clone());
+        let _ = dispatch.events.send(HandleEvent::CursorMove(mv));
+    }
+
+    extern "C" fn ddcci_data_handler_caller(data: evdi_ddcci_data, user_data: *mut c_void) {
+        let dispatch = unsafe { Self::dispatch_from_user_data(user_data) };
+
+        let len = data.buffer_length as usize;
+        let bytes = if data.buffer.is_null() || len == 0 {
+            Bytes::new()
+        } else {
+            // Safety: evdi hands us `buffer_length` valid bytes for the duration of this callback.
+            Bytes::copy_from_slice(unsafe {
+                std::slice::from_raw_parts(data.buffer as *const u8, len)
+            })
+        };
+
+        let _ = dispatch.events.send(HandleEvent::DdcCiData(DdcCiData { bytes }));
     }
 
-    fn buf_required(&self, id: &BufferID) -> &Buffer {
-        self.buffers.get(id).expect("Buffer not registered with handler")
+    /// Safety: user_data must be a valid pointer to the [`Dispatch`] owned by the driver task.
+    unsafe fn dispatch_from_user_data<'a>(user_data: *mut c_void) -> &'a Dispatch {
+        (user_data as *const Dispatch).as_ref().unwrap()
     }
 
     fn buf_required_mut(&mut self, id: &BufferID) -> &mut Buffer {
         self.buffers.get_mut(id).expect("Buffer not registered with handler")
     }
 
-    /// Takes a handle that has just been connected. Polls until ready.
-    fn new(handle: evdi_handle, device_config: DeviceConfig, ready_timeout: Duration) -> Self {
-        let poll_fd = unsafe { evdi_get_event_ready(handle) };
-        poll(
-            &mut [pollfd { fd: poll_fd, events: POLLIN, revents: 0 }],
-            Some(ready_timeout),
-        ).unwrap();
-
-        let (mode_sender, mode) = channel();
+    /// Takes a handle that has just been connected and spawns the driver task that dispatches
+    /// events off the evdi fd.
+    fn new(handle: evdi_handle, device_config: DeviceConfig) -> Self {
+        let poll_fd = unsafe { evdi_get_event_ready(handle) } as RawFd;
+
+        let (mode_sender, mode) = unbounded_channel();
+        let (update_ready_sender, update_ready) = unbounded_channel();
+        let (cursor_set_sender, cursor_set) = unbounded_channel();
+        let (cursor_move_sender, cursor_move) = unbounded_channel();
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+
+        let dispatch = Box::new(Dispatch {
+            mode: mode_sender,
+            update_ready: update_ready_sender,
+            cursor_set: cursor_set_sender,
+            cursor_move: cursor_move_sender,
+            events: events.clone(),
+            cursor_visible: AtomicBool::new(false),
+        });
+
+        let stop = Arc::new(Notify::new());
+        let driver = Self::spawn_driver(DriverHandle(handle), poll_fd, dispatch, stop.clone());
 
         Self {
             handle,
             device_config,
             buffers: HashMap::new(),
-            mode,
-            mode_sender,
+            mode: Mutex::new(mode),
+            update_ready,
+            cursor_set: Mutex::new(cursor_set),
+            cursor_move: Mutex::new(cursor_move),
+            events,
+            stop,
+            driver,
+            closed: false,
         }
     }
+
+    /// Spawn the task that registers the evdi fd with the tokio reactor and, on each readiness,
+    /// dispatches events through `evdi_handle_events`. It is the sole caller of
+    /// `evdi_handle_events`, which keeps dispatch single-threaded as the C library requires.
+    fn spawn_driver(handle: DriverHandle, poll_fd: RawFd, dispatch: Box<Dispatch>, stop: Arc<Notify>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let handle = handle;
+            let dispatch = dispatch;
+            let user_data = &*dispatch as *const Dispatch as *mut c_void;
+
+            match AsyncFd::new(poll_fd) {
+                Ok(async_fd) => loop {
+                    tokio::select! {
+                        res = async_fd.readable() => {
+                            let mut guard = match res {
+                                Ok(guard) => guard,
+                                Err(_) => break,
+                            };
+
+                            let mut ctx = evdi_event_context {
+                                dpms_handler: None,
+                                mode_changed_handler: Some(Self::mode_changed_handler_caller),
+                                update_ready_handler: Some(Self::update_ready_handler_caller),
+                                crtc_state_handler: None,
+                                cursor_set_handler: Some(Self::cursor_set_handler_caller),
+                                cursor_move_handler: Some(Self::cursor_move_handler_caller),
+                                ddcci_data_handler: Some(Self::ddcci_data_handler_caller),
+                                user_data,
+                            };
+                            unsafe { evdi_handle_events(handle.0, &mut ctx) };
+
+                            guard.clear_ready();
+                        }
+                        _ = stop.notified() => break,
+                    }
+                },
+                Err(err) => {
+                    eprintln!("Failed to register evdi fd with the reactor: {:?}", err);
+                }
+            }
+        })
+    }
 }
 
 impl Drop for Handle {
     fn drop(&mut self) {
-        unsafe {
-            evdi_disconnect(self.handle);
-            evdi_close(self.handle);
+        // We can't await the driver task here, so unlike `Self::close` we can't wait for it to
+        // actually stop before tearing the handle down. Prefer `Self::close` when you can await;
+        // this accepts the narrow pre-existing race of disconnecting/closing while the aborted
+        // task may still be mid-call inside `evdi_handle_events` as a pragmatic default.
+        self.driver.abort();
+        self.teardown();
+    }
+}
+
+/// The number of unconsumed [`HandleEvent`]s a [`HandleEvents`] stream can lag behind before the
+/// oldest are dropped.
+const EVENT_CHANNEL_CAPACITY: usize = 16;
+
+/// A stream of [`HandleEvent`]s forwarded by the driver task.
+///
+/// See [`Handle::events`].
+pub struct HandleEvents {
+    inner: BroadcastStream<HandleEvent>,
+}
+
+impl Stream for HandleEvents {
+    type Item = HandleEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match Pin::new(&mut self.inner).poll_next(cx) {
+                // Skip lag notifications; the consumer only cares about the events themselves.
+                Poll::Ready(Some(Err(_))) => continue,
+                Poll::Ready(Some(Ok(event))) => return Poll::Ready(Some(event)),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
         }
     }
 }
@@ -234,51 +488,266 @@ impl BufferID {
 pub struct Buffer {
     pub id: BufferID,
     version: u32,
-    buffer: Pin<Box<Vec<u8>>>,
+    backing: Backing,
     rects: Pin<Box<Vec<evdi_rect>>>,
     num_rects: i32,
     width: usize,
     height: usize,
     stride: usize,
     depth: usize,
-    update_ready: Receiver<()>,
-    update_ready_sender: Sender<()>,
+    format: DrmFormat,
+}
+
+/// The memory `evdi_buffer.buffer` points at.
+///
+/// A [`Buffer::new`] buffer owns a plain userspace allocation, while a buffer created from GBM or
+/// a dmabuf points directly at the imported scanout memory so `request_update` can avoid the
+/// per-frame copy out of the kernel.
+enum Backing {
+    /// A userspace-allocated, CPU-side buffer.
+    Vec(Pin<Box<Vec<u8>>>),
+    /// A dmabuf mmap'd into our address space.
+    Dmabuf(DmabufBacking),
+}
+
+impl Backing {
+    fn as_ptr(&self) -> *const u8 {
+        match self {
+            Backing::Vec(vec) => vec.as_ptr(),
+            Backing::Dmabuf(dmabuf) => dmabuf.ptr as *const u8,
+        }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Backing::Vec(vec) => vec.as_slice(),
+            // Safety: the mapping lives as long as the `DmabufBacking`, which lives as long as the
+            // `Buffer`, and `len` is the size we passed to `mmap`.
+            Backing::Dmabuf(dmabuf) => unsafe {
+                std::slice::from_raw_parts(dmabuf.ptr as *const u8, dmabuf.len)
+            },
+        }
+    }
+}
+
+impl fmt::Debug for Backing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backing::Vec(vec) => f.debug_tuple("Vec").field(&vec.len()).finish(),
+            Backing::Dmabuf(dmabuf) => f
+                .debug_struct("Dmabuf")
+                .field("fd", &dmabuf.fd)
+                .field("len", &dmabuf.len)
+                .finish(),
+        }
+    }
+}
+
+/// A dmabuf mmap'd into our address space, optionally keeping the GBM buffer object it was exported
+/// from alive. Unmapped and closed on drop.
+struct DmabufBacking {
+    ptr: *mut c_void,
+    len: usize,
+    fd: RawFd,
+    // Held only to keep the GBM allocation alive for the lifetime of the mapping.
+    _bo: Option<Box<dyn Any + Send>>,
+}
+
+impl Drop for DmabufBacking {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+            libc::close(self.fd);
+        }
+    }
 }
 
 /// Can't have more than 16
 /// see <https://displaylink.github.io/evdi/details/#grabbing-pixels>
 const MAX_RECTS_BUFFER_LEN: usize = 16;
 
-const BGRA_DEPTH: usize = 4;
+/// The format userspace-allocated buffers default to, matching the historical hardcoded BGRA
+/// (memory order B, G, R, A) layout.
+const DEFAULT_FORMAT: DrmFormat = DrmFormat::Argb8888;
+
+/// The number of bytes a single pixel of `format` occupies.
+fn bytes_per_pixel(format: DrmFormat) -> usize {
+    match format {
+        DrmFormat::Rgb565 | DrmFormat::Bgr565 => 2,
+        DrmFormat::Rgb888 | DrmFormat::Bgr888 => 3,
+        // Everything else we handle is a 32-bit packed format.
+        _ => 4,
+    }
+}
+
+/// Decode one pixel of `format` to 8-bit per channel R, G, B, expanding sub-8-bit channels.
+///
+/// DRM fourcc names list channels from the most- to least-significant byte of a little-endian
+/// word, so the bytes appear in memory in the reverse of the name, whatever the host's endianness.
+fn fourcc_to_rgb(format: DrmFormat, px: &[u8]) -> [u8; 3] {
+    match format {
+        DrmFormat::Argb8888 | DrmFormat::Xrgb8888 => [px[2], px[1], px[0]],
+        DrmFormat::Abgr8888 | DrmFormat::Xbgr8888 => [px[0], px[1], px[2]],
+        DrmFormat::Bgra8888 | DrmFormat::Bgrx8888 => [px[1], px[2], px[3]],
+        DrmFormat::Rgba8888 | DrmFormat::Rgbx8888 => [px[3], px[2], px[1]],
+        DrmFormat::Rgb565 => {
+            let v = u16::from_le_bytes([px[0], px[1]]);
+            let r = ((v >> 11) & 0x1f) as u8;
+            let g = ((v >> 5) & 0x3f) as u8;
+            let b = (v & 0x1f) as u8;
+            [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+        }
+        DrmFormat::Rgb888 => [px[2], px[1], px[0]],
+        DrmFormat::Bgr888 => [px[0], px[1], px[2]],
+        DrmFormat::Bgr565 => {
+            let v = u16::from_le_bytes([px[0], px[1]]);
+            let b = ((v >> 11) & 0x1f) as u8;
+            let g = ((v >> 5) & 0x3f) as u8;
+            let r = (v & 0x1f) as u8;
+            [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+        }
+        // Fall back to treating the first three bytes as R, G, B.
+        _ => [px[0], px.get(1).copied().unwrap_or(0), px.get(2).copied().unwrap_or(0)],
+    }
+}
 
 impl Buffer {
     /// Allocate a buffer to store the screen of a device with a specific mode.
+    ///
+    /// The buffer uses the default 32-bit BGRA layout; use [`Self::with_format`] to request a
+    /// specific [`DrmFormat`].
     pub fn new(id: BufferID, mode: &evdi_mode) -> Self {
+        Self::with_format(id, mode, DEFAULT_FORMAT)
+    }
+
+    /// Allocate a userspace buffer with an explicit pixel [`DrmFormat`].
+    ///
+    /// `stride`, `depth`, the line length of [`DirtyRect::bytes`] and the channel decoding of
+    /// [`DirtyRect::debug_write_to_ppm`] all derive from the fourcc, so formats other than 32-bit
+    /// BGRA (for example `Rgb565` or `Xrgb8888`) come out correctly sized, and the PPM output has
+    /// the right channel order. [`DirtyRect::bytes`] returns the pixels in the format's own memory
+    /// layout; it does not reorder channels.
+    pub fn with_format(id: BufferID, mode: &evdi_mode, format: DrmFormat) -> Self {
         let width = mode.width as usize;
         let height = mode.height as usize;
-        let bits_per_pixel = mode.bits_per_pixel as usize;
-        let stride = bits_per_pixel / 8 * width;
+        let stride = bytes_per_pixel(format) * width;
 
         let buffer = Box::pin(vec![0u8; height * stride]);
-        let rects = Box::pin(vec![evdi_rect { x1: 0, y1: 0, x2: 0, y2: 0 }; MAX_RECTS_BUFFER_LEN]);
 
-        let (update_ready_sender, update_ready) = channel();
+        Buffer::with_backing(id, mode, Backing::Vec(buffer), stride, format)
+    }
+
+    /// Allocate a userspace buffer from a raw DRM fourcc code.
+    ///
+    /// Returns [`UnrecognizedFourcc`] if the code does not name a known format.
+    pub fn with_fourcc(id: BufferID, mode: &evdi_mode, fourcc: u32) -> Result<Self, UnrecognizedFourcc> {
+        let format = DrmFormat::try_from(fourcc)?;
+        Ok(Self::with_format(id, mode, format))
+    }
+
+    /// Allocate a buffer backed by a GBM buffer object exported as a dmabuf.
+    ///
+    /// The buffer object is allocated with scanout/linear usage and mmap'd, so the pixels
+    /// `request_update` grabs live in memory that can be handed straight to a GL/Vulkan importer
+    /// or re-exported to a client over the Linux dmabuf protocol, avoiding the per-frame copy
+    /// [`DirtyRect::bytes`] performs.
+    pub fn from_gbm<T: AsRawFd + 'static>(
+        id: BufferID,
+        mode: &evdi_mode,
+        gbm: &gbm::Device<T>,
+    ) -> io::Result<Self> {
+        let bo = gbm
+            .create_buffer_object::<()>(
+                mode.width as u32,
+                mode.height as u32,
+                gbm::Format::Xrgb8888,
+                gbm::BufferObjectFlags::SCANOUT | gbm::BufferObjectFlags::LINEAR,
+            )
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+        let fd = bo.fd().map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        let stride = bo.stride().map_err(|err| io::Error::new(io::ErrorKind::Other, err))? as usize;
+        let modifier = u64::from(bo.modifier().unwrap_or(gbm::Modifier::Linear));
+
+        Self::from_dmabuf_inner(id, mode, fd, stride, modifier, DrmFormat::Xrgb8888, Some(Box::new(bo)))
+    }
+
+    /// Allocate a buffer pointing at an externally-owned dmabuf.
+    ///
+    /// `raw_fd` must refer to a dmabuf of at least `stride * mode.height` bytes laid out as
+    /// `modifier` describes. Only `DRM_FORMAT_MOD_LINEAR` is supported, since everything that
+    /// reads the buffer back (`request_update`, [`DirtyRect::bytes`]) treats it as linear,
+    /// row-major memory at `stride`-byte intervals; any other modifier returns an error instead of
+    /// silently misinterpreting tiled or compressed pixels. The fd is taken ownership of and
+    /// closed on drop.
+    pub fn from_dmabuf(
+        id: BufferID,
+        mode: &evdi_mode,
+        raw_fd: RawFd,
+        stride: usize,
+        modifier: u64,
+    ) -> io::Result<Self> {
+        Self::from_dmabuf_inner(id, mode, raw_fd, stride, modifier, DEFAULT_FORMAT, None)
+    }
 
-        let buf = Buffer {
+    fn from_dmabuf_inner(
+        id: BufferID,
+        mode: &evdi_mode,
+        fd: RawFd,
+        stride: usize,
+        modifier: u64,
+        format: DrmFormat,
+        bo: Option<Box<dyn Any + Send>>,
+    ) -> io::Result<Self> {
+        if modifier != u64::from(gbm::Modifier::Linear) {
+            unsafe { libc::close(fd) };
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "unsupported dmabuf modifier {:#x}: only DRM_FORMAT_MOD_LINEAR is readable as row-major pixels",
+                    modifier
+                ),
+            ));
+        }
+
+        let len = stride * mode.height as usize;
+
+        // Safety: `fd` is a dmabuf we own and `len` is derived from its stride and height.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+
+        let backing = Backing::Dmabuf(DmabufBacking { ptr, len, fd, _bo: bo });
+        Ok(Buffer::with_backing(id, mode, backing, stride, format))
+    }
+
+    fn with_backing(id: BufferID, mode: &evdi_mode, backing: Backing, stride: usize, format: DrmFormat) -> Self {
+        let rects = Box::pin(vec![evdi_rect { x1: 0, y1: 0, x2: 0, y2: 0 }; MAX_RECTS_BUFFER_LEN]);
+
+        Buffer {
             id,
             version: 0,
-            buffer,
+            backing,
             rects,
             num_rects: -1,
-            width,
-            height,
+            width: mode.width as usize,
+            height: mode.height as usize,
             stride,
-            depth: BGRA_DEPTH,
-            update_ready,
-            update_ready_sender,
-        };
-
-        buf
+            depth: bytes_per_pixel(format),
+            format,
+        }
     }
 
     /// The portions of the screen that changed before the last call to [`Handle::request_update`]
@@ -292,7 +761,7 @@ impl Buffer {
     fn sys(&mut self) -> evdi_buffer {
         evdi_buffer {
             id: self.id.0,
-            buffer: self.buffer.as_ptr() as *mut c_void,
+            buffer: self.backing.as_ptr() as *mut c_void,
             width: self.width as c_int,
             height: self.height as c_int,
             stride: self.stride as c_int,
@@ -333,7 +802,7 @@ impl<'a> DirtyRect<'a> {
 
     /// Copy and return the bytes this `DirtyRect` refers to.
     ///
-    /// You must not call [`Handle::update_buffer`] on the buffer this came from while this function
+    /// You must not call [`Handle::request_update`] on the buffer this came from while this function
     /// is running.
     pub fn bytes(&self) -> Option<ChunkedBytes> {
         if !self.is_valid() {
@@ -342,29 +811,83 @@ impl<'a> DirtyRect<'a> {
 
         let buf = self.buf;
 
+        let data = buf.backing.as_slice();
         let mut out = ChunkedBytes::with_profile(buf.width, self.buf.height);
         for line in 0..self.buf.height {
             let start_inclusive = buf.stride * line;
             let end_exclusive = start_inclusive + (buf.width * buf.depth);
             // TODO: Does this copy slow us down noticeably?
-            let bytes = Bytes::copy_from_slice(&buf.buffer[start_inclusive..end_exclusive]);
+            let bytes = Bytes::copy_from_slice(&data[start_inclusive..end_exclusive]);
             out.put_bytes(bytes);
         }
 
         Some(out)
     }
 
+    /// Copy and return only the bytes inside this `DirtyRect`'s own bounds.
+    ///
+    /// Unlike [`Self::bytes`], which copies every line of the whole frame, this copies just the
+    /// `(x2 - x1) * depth` bytes of each line in `y1..y2`, so a small update stays a small copy.
+    /// Returns the cropped pixels alongside the [`DirtyRectBounds`] they cover, or `None` if the
+    /// underlying [`Buffer`] has been updated since, or if the rect itself is out of bounds or
+    /// inverted: the kernel module hands us raw rects, so a stale or malformed one is treated the
+    /// same as an invalid version rather than trusted blindly.
+    ///
+    /// The same concurrency requirements as [`Self::bytes`] apply.
+    pub fn cropped_bytes(&self) -> Option<(ChunkedBytes, DirtyRectBounds)> {
+        if !self.is_valid() {
+            return None;
+        }
+
+        let buf = self.buf;
+        let bounds = DirtyRectBounds::new(buf.rects[self.i]);
+
+        let width = buf.width as u32;
+        let height = buf.height as u32;
+        if bounds.x1 > bounds.x2
+            || bounds.y1 > bounds.y2
+            || bounds.x2 > width
+            || bounds.y2 > height
+        {
+            return None;
+        }
+
+        let x1 = bounds.x1 as usize;
+        let y1 = bounds.y1 as usize;
+        let x2 = bounds.x2 as usize;
+        let y2 = bounds.y2 as usize;
+
+        let data = buf.backing.as_slice();
+        let row_len = (x2 - x1) * buf.depth;
+
+        let mut out = ChunkedBytes::with_profile(row_len, y2 - y1);
+        for line in y1..y2 {
+            let start_inclusive = buf.stride * line + x1 * buf.depth;
+            let end_exclusive = start_inclusive + row_len;
+            out.put_bytes(Bytes::copy_from_slice(&data[start_inclusive..end_exclusive]));
+        }
+
+        Some((out, bounds))
+    }
+
     /// Write the pixels to a file in the unoptimized image format [PPM].
     ///
     /// This is useful when debugging, as you can open the file in an image viewer and see if the
     /// buffer is processed correctly.
-    /// 
+    ///
     /// The same requirements as [`Self::bytes`] apply.
     ///
     /// [PPM]: http://netpbm.sourceforge.net/doc/ppm.html
     pub fn debug_write_to_ppm(&self, f: &mut File) -> Option<io::Result<()>> {
         if let Some(bytes) = self.bytes() {
-            Some(Self::debug_write_bytes_to_ppm(bytes, self.buf.width, self.buf.height, f))
+            Some(Self::debug_write_bytes_to_ppm(
+                bytes,
+                self.buf.format,
+                self.buf.depth,
+                self.buf.width,
+                self.buf.height,
+                f,
+            ))
         } else {
             None
         }
@@ -372,6 +895,8 @@ impl<'a> DirtyRect<'a> {
 
     fn debug_write_bytes_to_ppm(
         bytes: ChunkedBytes,
+        format: DrmFormat,
+        depth: usize,
         width: usize,
         height: usize,
         f: &mut File
@@ -382,13 +907,9 @@ impl<'a> DirtyRect<'a> {
         Self::write_line(f, "255\n")?;
 
         for chunk in bytes.into_chunks() {
-            for chunk in chunk.as_ref().chunks_exact(BGRA_DEPTH) {
-                let b = chunk[0];
-                let g = chunk[1];
-                let r = chunk[2];
-                let _a = chunk[3];
-
-                f.write_all(&[r, g, b])?;
+            for px in chunk.as_ref().chunks_exact(depth) {
+                // Expands sub-8-bit formats like Rgb565 to 8-bit RGB.
+                f.write_all(&fourcc_to_rgb(format, px))?;
             }
         }
 
@@ -409,6 +930,7 @@ impl<'a> DirtyRect<'a> {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
 pub struct DirtyRectBounds {
     x1: u32,
     y1: u32,
@@ -426,15 +948,81 @@ impl DirtyRectBounds {
         }
     }
 
-    fn width(&self) -> u32 {
+    pub fn x1(&self) -> u32 {
+        self.x1
+    }
+
+    pub fn y1(&self) -> u32 {
+        self.y1
+    }
+
+    pub fn x2(&self) -> u32 {
+        self.x2
+    }
+
+    pub fn y2(&self) -> u32 {
+        self.y2
+    }
+
+    pub fn width(&self) -> u32 {
         self.x2 - self.x1
     }
 
-    fn height(&self) -> u32 {
+    pub fn height(&self) -> u32 {
         self.y2 - self.y1
     }
 }
 
+/// A single changed sub-rectangle of a frame and the pixels inside it.
+pub struct Tile {
+    pub bounds: DirtyRectBounds,
+    pub bytes: ChunkedBytes,
+}
+
+/// The set of tiles that changed in a single [`Handle::request_update`].
+pub struct Frame {
+    pub tiles: Vec<Tile>,
+}
+
+/// Turns successive buffer updates into only the regions that changed.
+///
+/// A screencast / remote-desktop consumer (VNC/RFB, a Wayland screencopy sink, ...) can drive an
+/// encoder per buffer and forward each [`Frame`]'s tiles, keeping bandwidth proportional to
+/// on-screen change instead of re-sending the whole framebuffer every update.
+pub struct FrameEncoder {
+    id: BufferID,
+}
+
+impl FrameEncoder {
+    pub fn new(id: BufferID) -> Self {
+        Self { id }
+    }
+
+    /// The buffer this encoder tracks.
+    pub fn buffer_id(&self) -> BufferID {
+        self.id
+    }
+
+    /// Extract the changed tiles reported by the most recent update of `buf`.
+    ///
+    /// `buf` must be the buffer this encoder tracks, freshly returned from
+    /// [`Handle::request_update`].
+    pub fn encode(&self, buf: &Buffer) -> Frame {
+        let tiles = buf
+            .dirty_rects()
+            .iter()
+            .filter_map(|rect| rect.cropped_bytes().map(|(bytes, bounds)| Tile { bounds, bytes }))
+            .collect();
+        Frame { tiles }
+    }
+
+    /// Request an update of the tracked buffer and return only its changed tiles.
+    pub async fn next_frame(&self, handle: &mut Handle) -> Option<Frame> {
+        let buf = handle.request_update(&self.id).await?;
+        Some(self.encode(buf))
+    }
+}
+
 /// Automatically closed on drop
 #[derive(Debug)]
 pub struct UnconnectedHandle {
@@ -442,18 +1030,21 @@ pub struct UnconnectedHandle {
 }
 
 impl UnconnectedHandle {
-    /// Connect to an handle and block until ready.
+    /// Connect to a handle.
     ///
-    /// ```
+    /// Returns as soon as `evdi_connect` has been issued; it does not block waiting for the
+    /// display to become ready. Await [`Handle::receive_mode`] to find out when a mode is
+    /// available.
+    ///
+    /// ```no_run
     /// # use evdi::device::Device;
     /// # use evdi::device_config::DeviceConfig;
-    /// # use std::time::Duration;
     /// let device: Device = Device::get().unwrap();
     /// let handle = device
     ///     .open()
-    ///     .connect(&DeviceConfig::sample(), Duration::from_secs(1));
+    ///     .connect(&DeviceConfig::sample());
     /// ```
-    pub fn connect(self, config: &DeviceConfig, ready_timeout: Duration) -> Handle {
+    pub fn connect(self, config: &DeviceConfig) -> Handle {
         // NOTE: We deliberately take ownership to ensure a handle is connected at most once.
 
         let config: DeviceConfig = config.to_owned();
@@ -467,7 +1058,7 @@ impl UnconnectedHandle {
             );
         }
 
-        Handle::new(self.handle, config, ready_timeout)
+        Handle::new(self.handle, config)
     }
 
     pub(crate) fn new(handle: evdi_handle) -> Self {
@@ -483,61 +1074,105 @@ impl Drop for UnconnectedHandle {
 
 #[cfg(test)]
 mod tests {
-    use std::thread::sleep;
-    use std::time::Duration;
-
     use crate::device::Device;
     use crate::device_config::DeviceConfig;
 
     use super::*;
 
-    const TIMEOUT: Duration = Duration::from_secs(1);
+    #[test]
+    fn fourcc_to_rgb_bgr565_mirrors_rgb565_with_r_and_b_swapped() {
+        // Top 5 bits set, everything else zero: in Rgb565 that's R maxed, but Rgb565 and Bgr565
+        // are the same 5/6/5 bitfield with the R and B ends swapped, so the same bits mean B
+        // maxed in Bgr565.
+        let px = 0xF800u16.to_le_bytes();
+
+        assert_eq!(fourcc_to_rgb(DrmFormat::Rgb565, &px), [255, 0, 0]);
+        assert_eq!(fourcc_to_rgb(DrmFormat::Bgr565, &px), [0, 0, 255]);
+    }
+
+    #[test]
+    fn from_dmabuf_rejects_non_linear_modifier() {
+        let mut mode: evdi_mode = unsafe { std::mem::zeroed() };
+        mode.width = 64;
+        mode.height = 64;
+
+        let modifier = u64::from(gbm::Modifier::Linear) + 1;
+        let err = Buffer::from_dmabuf(BufferID::new(1), &mode, -1, 64 * 4, modifier).unwrap_err();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn cursor_move_reports_visibility_from_last_cursor_set() {
+        let (mode, _mode_rx) = unbounded_channel();
+        let (update_ready, _update_ready_rx) = unbounded_channel();
+        let (cursor_set, _cursor_set_rx) = unbounded_channel();
+        let (cursor_move, mut cursor_move_rx) = unbounded_channel();
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+
+        let dispatch = Dispatch {
+            mode,
+            update_ready,
+            cursor_set,
+            cursor_move,
+            events,
+            cursor_visible: AtomicBool::new(false),
+        };
+        let user_data = &dispatch as *const Dispatch as *mut c_void;
+
+        // `evdi_cursor_move` carries no visibility flag of its own; it's reported from the most
+        // recent cursor-set event instead.
+        let mut set: evdi_cursor_set = unsafe { std::mem::zeroed() };
+        set.enabled = 1;
+        Handle::cursor_set_handler_caller(set, user_data);
+
+        let mv: evdi_cursor_move = unsafe { std::mem::zeroed() };
+        Handle::cursor_move_handler_caller(mv, user_data);
+
+        assert!(cursor_move_rx.recv().await.unwrap().visible);
+    }
 
     fn connect() -> Handle {
         Device::get().unwrap()
             .open()
-            .connect(&DeviceConfig::sample(), TIMEOUT)
+            .connect(&DeviceConfig::sample())
     }
 
-    #[test]
-    fn can_connect() {
+    #[tokio::test]
+    async fn can_connect() {
         connect();
     }
 
-    #[test]
-    fn can_enable_cursor_events() {
+    #[tokio::test]
+    async fn can_enable_cursor_events() {
         connect().enable_cursor_events(true);
     }
 
-    #[test]
-    fn can_receive_mode() {
-        let mut handle = connect();
-        handle.request_events();
-        let mode = handle.receive_mode(TIMEOUT).unwrap();
+    #[tokio::test]
+    async fn can_receive_mode() {
+        let handle = connect();
+        let mode = handle.receive_mode().await.unwrap();
         assert!(mode.height > 100);
     }
 
-    #[test]
-    fn can_create_buffer() {
-        let mut handle = connect();
-        handle.request_events();
-        let mode = handle.receive_mode(TIMEOUT).unwrap();
+    #[tokio::test]
+    async fn can_create_buffer() {
+        let handle = connect();
+        let mode = handle.receive_mode().await.unwrap();
         Buffer::new(BufferID(1), &mode);
     }
 
-    #[test]
-    fn can_access_buffer_sys() {
-        let mut handle = connect();
-        handle.request_events();
-        let mode = handle.receive_mode(TIMEOUT).unwrap();
+    #[tokio::test]
+    async fn can_access_buffer_sys() {
+        let handle = connect();
+        let mode = handle.receive_mode().await.unwrap();
         Buffer::new(BufferID(1), &mode).sys();
     }
 
-    #[test]
-    fn can_register_buffers() {
+    #[tokio::test]
+    async fn can_register_buffers() {
         let mut handle = connect();
-        handle.request_events();
-        let mode = handle.receive_mode(TIMEOUT).unwrap();
+        let mode = handle.receive_mode().await.unwrap();
 
         let buf1 = Buffer::new(BufferID(1), &mode);
         let buf2 = Buffer::new(BufferID(2), &mode);
@@ -546,47 +1181,45 @@ mod tests {
         handle.register_buffer(buf2);
     }
 
-    #[test]
-    fn update_includes_at_least_one_dirty_rect() {
+    #[tokio::test]
+    async fn update_includes_at_least_one_dirty_rect() {
         let mut handle = connect();
-        let buf = get_update(&mut handle);
+        let buf = get_update(&mut handle).await;
 
         assert!(buf.dirty_rects().len() > 0);
     }
 
-    #[test]
-    fn update_can_be_called_multiple_times() {
+    #[tokio::test]
+    async fn update_can_be_called_multiple_times() {
         let mut handle = connect();
 
-        handle.request_events();
-        let mode = handle.receive_mode(TIMEOUT).unwrap();
+        let mode = handle.receive_mode().await.unwrap();
 
         let buf_id = BufferID::new(1);
         handle.register_buffer(Buffer::new(buf_id, &mode));
 
         for _ in 0..10 {
-            handle.request_update(&buf_id, TIMEOUT).unwrap();
+            handle.request_update(&buf_id).await.unwrap();
         }
     }
 
-    fn get_update(handle: &mut Handle) -> &Buffer {
-        handle.request_events();
-        let mode = handle.receive_mode(TIMEOUT).unwrap();
+    async fn get_update(handle: &mut Handle) -> &Buffer {
+        let mode = handle.receive_mode().await.unwrap();
         let buf_id = BufferID::new(1);
         handle.register_buffer(Buffer::new(buf_id, &mode));
 
         // Settle
         for _ in 0..20 {
-            handle.request_update(&buf_id, TIMEOUT).unwrap();
+            handle.request_update(&buf_id).await.unwrap();
         }
 
-        handle.request_update(&buf_id, TIMEOUT).unwrap()
+        handle.request_update(&buf_id).await.unwrap()
     }
 
-    #[test]
-    fn bytes_is_non_empty() {
+    #[tokio::test]
+    async fn bytes_is_non_empty() {
         let mut handle = connect();
-        let buf = get_update(&mut handle);
+        let buf = get_update(&mut handle).await;
         let rects = buf.dirty_rects();
         let rect = &rects[0];
 
@@ -604,10 +1237,44 @@ mod tests {
         assert!(avg > 10, "avg byte {:?} < 10, suggesting we aren't correctly grabbing the screen", avg);
     }
 
-    #[test]
-    fn can_output_debug() {
+    #[tokio::test]
+    async fn cropped_bytes_matches_bounds() {
         let mut handle = connect();
-        let buf = get_update(&mut handle);
+        let buf = get_update(&mut handle).await;
+        let rects = buf.dirty_rects();
+        let rect = &rects[0];
+
+        let (bytes, bounds) = rect.cropped_bytes().unwrap();
+
+        let expected: usize = bounds.width() as usize * bounds.height() as usize * buf.depth;
+        let actual: usize = bytes.into_chunks().map(|chunk| chunk.len()).sum();
+        assert_eq!(actual, expected);
+    }
+
+    #[tokio::test]
+    async fn frame_encoder_emits_a_tile_per_dirty_rect() {
+        let mut handle = connect();
+        let buf_id = BufferID::new(1);
+
+        let mode = handle.receive_mode().await.unwrap();
+        handle.register_buffer(Buffer::new(buf_id, &mode));
+        for _ in 0..20 {
+            handle.request_update(&buf_id).await.unwrap();
+        }
+
+        let encoder = FrameEncoder::new(buf_id);
+        let buf = handle.request_update(&buf_id).await.unwrap();
+        let expected = buf.dirty_rects().len();
+        let frame = encoder.encode(buf);
+
+        assert!(!frame.tiles.is_empty());
+        assert_eq!(frame.tiles.len(), expected);
+    }
+
+    #[tokio::test]
+    async fn can_output_debug() {
+        let mut handle = connect();
+        let buf = get_update(&mut handle).await;
         let rects = buf.dirty_rects();
         let rect = &rects[0];
 
@@ -619,4 +1286,4 @@ mod tests {
 
         rect.debug_write_to_ppm(&mut f).unwrap().unwrap();
     }
-}
\ No newline at end of file
+}